@@ -0,0 +1,181 @@
+//! Compact binary recording format: a direction tag byte (`0` = sent, `1` =
+//! received), a LEB128 varint length, then that many payload bytes.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use minidsp::utils::recorder::Message;
+use tokio_util::codec::{Decoder, Encoder};
+
+const TAG_SENT: u8 = 0;
+const TAG_RECEIVED: u8 = 1;
+
+/// A [`Decoder`]/[`Encoder`] for the compact binary recording format.
+#[derive(Default)]
+pub struct BinaryCodec;
+
+impl BinaryCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Max bytes a LEB128-encoded `u64` can occupy (`ceil(64 / 7)`).
+const MAX_VARINT_LEN: usize = 10;
+
+/// Reads a LEB128 varint from the front of `src`, returning `None` if the
+/// buffer doesn't yet contain a complete varint (e.g. it ends mid-continuation).
+/// On success, returns the decoded value and the number of bytes it occupied.
+/// Errors if more than [`MAX_VARINT_LEN`] bytes are seen without a terminator,
+/// since that can't encode a valid `u64`.
+fn read_varint(src: &[u8]) -> Result<Option<(u64, usize)>, std::io::Error> {
+    let mut value: u64 = 0;
+    for (i, &byte) in src.iter().take(MAX_VARINT_LEN).enumerate() {
+        value |= u64::from(byte & 0x7F) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, i + 1)));
+        }
+    }
+    if src.len() >= MAX_VARINT_LEN {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "varint longer than the maximum length for a u64",
+        ));
+    }
+    Ok(None)
+}
+
+fn write_varint(mut value: u64, dst: &mut BytesMut) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            dst.put_u8(byte);
+            break;
+        } else {
+            dst.put_u8(byte | 0x80);
+        }
+    }
+}
+
+impl Decoder for BinaryCodec {
+    type Item = Message;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.is_empty() {
+            return Ok(None);
+        }
+        let tag = src[0];
+
+        let (len, varint_len) = match read_varint(&src[1..])? {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        let len = len as usize;
+        let header_len = 1 + varint_len;
+
+        if src.len() < header_len + len {
+            src.reserve(header_len + len - src.len());
+            return Ok(None);
+        }
+
+        src.advance(header_len);
+        let payload = src.split_to(len);
+        let payload = Bytes::from(payload).to_vec();
+
+        let msg = match tag {
+            TAG_SENT => Message::Sent(payload),
+            TAG_RECEIVED => Message::Received(payload),
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("unknown binary recording direction tag: {}", tag),
+                ))
+            }
+        };
+
+        Ok(Some(msg))
+    }
+}
+
+impl Encoder<Message> for BinaryCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let (tag, payload) = match item {
+            Message::Sent(data) => (TAG_SENT, data),
+            Message::Received(data) => (TAG_RECEIVED, data),
+        };
+
+        dst.reserve(1 + 10 + payload.len());
+        dst.put_u8(tag);
+        write_varint(payload.len() as u64, dst);
+        dst.extend_from_slice(&payload);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut codec = BinaryCodec::new();
+        let mut buf = BytesMut::new();
+        codec
+            .encode(Message::Sent(vec![1, 2, 3]), &mut buf)
+            .unwrap();
+        codec
+            .encode(Message::Received(vec![4, 5]), &mut buf)
+            .unwrap();
+
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Message::Sent(data)) if data == [1, 2, 3]
+        ));
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Message::Received(data)) if data == [4, 5]
+        ));
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn partial_frame_yields_none() {
+        let mut codec = BinaryCodec::new();
+        let mut full = BytesMut::new();
+        codec
+            .encode(Message::Sent(vec![1, 2, 3, 4, 5]), &mut full)
+            .unwrap();
+
+        for i in 0..full.len() {
+            let mut partial = BytesMut::from(&full[..i]);
+            assert!(codec.decode(&mut partial).unwrap().is_none());
+        }
+        assert!(codec.decode(&mut full).unwrap().is_some());
+    }
+
+    #[test]
+    fn partial_varint_spanning_reads_yields_none() {
+        let mut codec = BinaryCodec::new();
+        // Tag byte, followed by a (non-minimal, 2-byte) varint whose
+        // continuation byte hasn't arrived yet.
+        let mut buf = BytesMut::from(&[TAG_SENT, 0x81][..]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+
+        buf.extend_from_slice(&[0x00, 0xAA]);
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Message::Sent(data)) if data == [0xAA]
+        ));
+    }
+
+    #[test]
+    fn overlong_varint_errors_instead_of_panicking() {
+        let mut codec = BinaryCodec::new();
+        let mut buf = BytesMut::from(&[TAG_SENT][..]);
+        buf.extend_from_slice(&[0xFF; 11]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+}
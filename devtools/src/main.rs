@@ -7,26 +7,108 @@ use std::{
 };
 
 use anyhow::Result;
+use async_compression::{
+    tokio::{bufread::ZstdDecoder, write::ZstdEncoder},
+    Level,
+};
 use bimap::BiMap;
 use clap::{self as clap, Clap};
 use codegen::{
     c8x12v2, ddrc24, ddrc88bm, generate_static_config, m10x10hd, m2x4, m2x4hd, m4x10hd, msharc4x8,
     nanodigi2x8, shd, spec::Device,
 };
-use futures::{Stream, StreamExt};
+use futures::{SinkExt, Stream, StreamExt};
 use minidsp::{
     commands::Commands,
     device::{self, DeviceKind},
+    transport,
     utils::{decoder, recorder},
+    MiniDSP,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
 };
-use tokio::{fs::File, io::AsyncReadExt};
 use tokio_util::{
-    codec::{Decoder, LinesCodec},
+    codec::{FramedRead, FramedWrite, LinesCodec},
     io::StreamReader,
 };
 
+use binary::BinaryCodec;
+
+/// Magic bytes identifying a zstd-compressed stream
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+mod binary;
 mod codegen;
 
+/// On-disk recording format: either the original text-based one (hex-encoded,
+/// one frame per line) or the compact length-delimited binary one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordingFormat {
+    Text,
+    Binary,
+}
+
+impl std::str::FromStr for RecordingFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(RecordingFormat::Text),
+            "binary" => Ok(RecordingFormat::Binary),
+            _ => Err(anyhow::anyhow!(
+                "invalid recording format `{}` (expected `text` or `binary`)",
+                s
+            )),
+        }
+    }
+}
+
+impl RecordingFormat {
+    /// Guesses the format from a file's extension, defaulting to `Text`.
+    /// A trailing `.zst` (a zstd-compressed recording, which
+    /// [`open_maybe_compressed`] decompresses transparently) is stripped first,
+    /// so `capture.bin.zst` still detects as `Binary` rather than falling
+    /// through on the compression suffix.
+    fn detect(path: &Path) -> Self {
+        let stripped;
+        let path = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("zst") => {
+                stripped = path.with_extension("");
+                &stripped
+            }
+            _ => path,
+        };
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("bin") => RecordingFormat::Binary,
+            _ => RecordingFormat::Text,
+        }
+    }
+}
+
+/// Frames `reader` using the codec matching `format`, yielding decoded
+/// [`recorder::Message`]s regardless of which on-disk format was used.
+fn framed_messages(
+    reader: Box<dyn AsyncRead + Unpin + Send>,
+    format: RecordingFormat,
+) -> std::pin::Pin<Box<dyn Stream<Item = recorder::Message> + Send>> {
+    match format {
+        RecordingFormat::Text => {
+            let framed = FramedRead::new(reader, LinesCodec::new());
+            Box::pin(
+                framed.filter_map(|x| async { recorder::Message::from_string(x.ok()?.as_str()) }),
+            )
+        }
+        RecordingFormat::Binary => {
+            let framed = FramedRead::new(reader, BinaryCodec::new());
+            Box::pin(framed.filter_map(|x| async { x.ok() }))
+        }
+    }
+}
+
 #[derive(Clap, Debug)]
 #[clap(version=env!("CARGO_PKG_VERSION"), author=env!("CARGO_PKG_AUTHORS"))]
 struct Opts {
@@ -41,6 +123,11 @@ enum SubCommand {
         input: PathBuf,
         #[clap(name = "force-kind", long)]
         force_kind: Option<DeviceKind>,
+
+        /// Recording format to parse `input` as (defaults to autodetecting from
+        /// the file extension)
+        #[clap(long)]
+        format: Option<RecordingFormat>,
     },
 
     /// Dumps the bulk-loaded parameter data into a file
@@ -49,12 +136,73 @@ enum SubCommand {
         output: PathBuf,
         #[clap(long)]
         skip: Option<usize>,
+
+        /// Compress the output with zstd, optionally at the given level (1-22)
+        #[clap(long, name = "level", min_values = 0, max_values = 1)]
+        compress: Option<Option<i32>>,
+
+        /// Recording format to parse `input` as (defaults to autodetecting from
+        /// the file extension)
+        #[clap(long)]
+        format: Option<RecordingFormat>,
+    },
+
+    /// Pretty-prints protocol traffic as it arrives, instead of waiting for EOF
+    DecodeLive {
+        /// `-` for stdin, or `tcp://host:port` to follow a TCP stream
+        source: String,
+        #[clap(name = "force-kind", long)]
+        force_kind: Option<DeviceKind>,
+    },
+
+    /// Replays the `Sent` commands of a recording against a live MiniDSP
+    Replay {
+        input: PathBuf,
+
+        /// Transport URL of the target device, e.g. `tcp://192.168.1.50:5333` or `usb:0`
+        transport: String,
+
+        /// Only replay `Commands::BulkLoad` frames, for restoring a saved configuration
+        #[clap(long)]
+        only_bulk: bool,
+
+        /// Commands per second to replay at (defaults to as fast as the device acks)
+        #[clap(long)]
+        rate: Option<f64>,
+
+        /// Print the commands that would be sent without connecting to a device
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Transcodes a recording between the text and binary formats
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+
+        /// Format of `input` (defaults to autodetecting from its extension)
+        #[clap(long)]
+        from: Option<RecordingFormat>,
+
+        /// Format to write `output` as (defaults to autodetecting from its extension)
+        #[clap(long)]
+        to: Option<RecordingFormat>,
     },
 
     Codegen {
         /// The directory prefix where generated files should be written
         /// This should map to minidsp_protocol/src/device/
         output: PathBuf,
+
+        /// A directory of device spec files (`.toml` or `.json`) to generate
+        /// from instead of the built-in targets
+        #[clap(long)]
+        input: Option<PathBuf>,
+
+        /// Only generate the device whose spec file stem matches this name
+        /// (requires `--input`)
+        #[clap(long)]
+        device: Option<String>,
     },
 }
 
@@ -64,35 +212,101 @@ pub async fn main() -> Result<()> {
     let opts: Opts = Opts::parse();
 
     match opts.cmd {
-        SubCommand::Decode { input, force_kind } => {
-            let file = File::open(input).await?;
-            let framed = LinesCodec::new().framed(file);
-            let messages =
-                framed.filter_map(|x| async { recorder::Message::from_string(x.ok()?.as_str()) });
-            decode(messages, force_kind).await?;
+        SubCommand::Decode {
+            input,
+            force_kind,
+            format,
+        } => {
+            let format = format.unwrap_or_else(|| RecordingFormat::detect(&input));
+            let reader = open_maybe_compressed(input).await?;
+            let messages = framed_messages(reader, format);
+            decode(messages, force_kind, false).await?;
         }
         SubCommand::DumpBulk {
             input,
             output,
             skip,
+            compress,
+            format,
         } => {
-            let file = File::open(input).await?;
-            let framed = LinesCodec::new().framed(file);
-            let messages =
-                framed.filter_map(|x| async { recorder::Message::from_string(x.ok()?.as_str()) });
-            dump(output, skip, messages).await?;
+            let format = format.unwrap_or_else(|| RecordingFormat::detect(&input));
+            let reader = open_maybe_compressed(input).await?;
+            let messages = framed_messages(reader, format);
+            dump(output, skip, compress, messages).await?;
         }
-        SubCommand::Codegen { output } => {
-            codegen_main(output)?;
+        SubCommand::DecodeLive { source, force_kind } => {
+            let reader = open_live_source(&source).await?;
+            let messages = framed_messages(reader, RecordingFormat::Text);
+            decode(messages, force_kind, true).await?;
+        }
+        SubCommand::Replay {
+            input,
+            transport,
+            only_bulk,
+            rate,
+            dry_run,
+        } => {
+            replay(input, transport, only_bulk, rate, dry_run).await?;
+        }
+        SubCommand::Convert {
+            input,
+            output,
+            from,
+            to,
+        } => {
+            convert(input, output, from, to).await?;
+        }
+        SubCommand::Codegen {
+            output,
+            input,
+            device,
+        } => {
+            codegen_main(output, input, device)?;
         }
     }
 
     Ok(())
 }
 
+/// Opens `input`, transparently wrapping it in a [`ZstdDecoder`] if its first
+/// bytes match the zstd frame magic number.
+async fn open_maybe_compressed(input: PathBuf) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
+    let mut file = File::open(input).await?;
+
+    let mut magic = [0u8; 4];
+    let n = file.read(&mut magic).await?;
+    file.seek(std::io::SeekFrom::Start(0)).await?;
+
+    if n == magic.len() && magic == ZSTD_MAGIC {
+        Ok(Box::new(ZstdDecoder::new(BufReader::new(file))))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// Opens a live streaming source for [`SubCommand::DecodeLive`]: `-` reads
+/// stdin, while a `tcp://host:port` address connects to that address and
+/// streams its traffic.
+async fn open_live_source(source: &str) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
+    if source == "-" {
+        return Ok(Box::new(tokio::io::stdin()));
+    }
+
+    if let Some(addr) = source.strip_prefix("tcp://") {
+        let stream = TcpStream::connect(addr).await?;
+        return Ok(Box::new(stream));
+    }
+
+    Err(anyhow::anyhow!(
+        "invalid live source `{}` (expected `-` or `tcp://host:port`)",
+        source
+    ))
+}
+
 async fn dump(
     output: PathBuf,
     skip: Option<usize>,
+    compress: Option<Option<i32>>,
     framed: impl Stream<Item = recorder::Message>,
 ) -> Result<()> {
     // Only keep bulk load commands
@@ -106,7 +320,7 @@ async fn dump(
         });
 
     let mut reader = Box::pin(StreamReader::new(f));
-    let mut output = File::create(output).await?;
+    let output = File::create(output).await?;
 
     if let Some(skip) = skip {
         tokio::io::copy(
@@ -116,7 +330,106 @@ async fn dump(
         .await?;
     }
 
-    tokio::io::copy(&mut reader, &mut output).await?;
+    match compress {
+        Some(level) => {
+            let level = level.map(Level::Precise).unwrap_or(Level::Default);
+            let mut output = ZstdEncoder::with_quality(output, level);
+            tokio::io::copy(&mut reader, &mut output).await?;
+            output.shutdown().await?;
+        }
+        None => {
+            let mut output = output;
+            tokio::io::copy(&mut reader, &mut output).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Re-issues the `Sent` commands of a recording against a live device.
+async fn replay(
+    input: PathBuf,
+    transport: String,
+    only_bulk: bool,
+    rate: Option<f64>,
+    dry_run: bool,
+) -> Result<()> {
+    if let Some(rate) = rate {
+        if !(rate > 0.0) || !rate.is_finite() {
+            return Err(anyhow::anyhow!(
+                "--rate must be a positive, finite number of commands per second"
+            ));
+        }
+    }
+
+    let format = RecordingFormat::detect(&input);
+    let reader = open_maybe_compressed(input).await?;
+    let messages = framed_messages(reader, format);
+
+    let commands = messages
+        .filter_map(recorder::decode_sent_commands)
+        .filter_map(|cmd| async move {
+            if only_bulk {
+                matches!(cmd, Commands::BulkLoad { .. }).then_some(cmd)
+            } else {
+                Some(cmd)
+            }
+        });
+    tokio::pin!(commands);
+
+    let device = if dry_run {
+        None
+    } else {
+        let transport = transport::open_url(&transport).await?;
+        Some(MiniDSP::new(transport))
+    };
+
+    let mut n = 0;
+    while let Some(cmd) = commands.next().await {
+        n += 1;
+        if let Some(device) = &device {
+            device.roundtrip(cmd).await?;
+        } else {
+            println!("{}: {:?}", n, cmd);
+        }
+
+        if let Some(rate) = rate {
+            tokio::time::sleep(std::time::Duration::from_secs_f64(1.0 / rate)).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Transcodes a recording from `from` to `to`, auto-detecting either format
+/// from the corresponding file's extension when not given explicitly.
+async fn convert(
+    input: PathBuf,
+    output: PathBuf,
+    from: Option<RecordingFormat>,
+    to: Option<RecordingFormat>,
+) -> Result<()> {
+    let from = from.unwrap_or_else(|| RecordingFormat::detect(&input));
+    let to = to.unwrap_or_else(|| RecordingFormat::detect(&output));
+
+    let reader = open_maybe_compressed(input).await?;
+    let mut messages = framed_messages(reader, from);
+    let output = File::create(output).await?;
+
+    match to {
+        RecordingFormat::Text => {
+            let mut writer = FramedWrite::new(output, LinesCodec::new());
+            while let Some(msg) = messages.next().await {
+                writer.send(msg.to_string()).await?;
+            }
+        }
+        RecordingFormat::Binary => {
+            let mut writer = FramedWrite::new(output, BinaryCodec::new());
+            while let Some(msg) = messages.next().await {
+                writer.send(msg).await?;
+            }
+        }
+    }
 
     Ok(())
 }
@@ -124,6 +437,7 @@ async fn dump(
 async fn decode(
     framed: impl Stream<Item = recorder::Message>,
     device_kind: Option<DeviceKind>,
+    live: bool,
 ) -> Result<()> {
     let mut decoder = {
         use termcolor::{ColorChoice, StandardStream};
@@ -152,6 +466,9 @@ async fn decode(
                 decoder.feed_recv(&data);
             }
         }
+        if live {
+            std::io::Write::flush(&mut std::io::stdout())?;
+        }
     }
 
     Ok(())
@@ -163,6 +480,26 @@ pub trait Target {
     fn device() -> Device;
 }
 
+/// The data-driven equivalent of [`Target`], deserialized from a spec file
+/// instead of being hardcoded as a Rust impl. `symbols` mirrors the layout of
+/// a [`BiMap<String, usize>`] since bimap itself doesn't implement `serde`.
+/// (`codegen::spec::Device` itself derives `serde::Deserialize`.)
+#[derive(serde::Deserialize, Debug)]
+struct DeviceSpec {
+    device: Device,
+    symbols: Vec<(String, usize)>,
+}
+
+impl DeviceSpec {
+    fn symbol_map(&self) -> BiMap<String, usize> {
+        self.symbols.iter().cloned().collect()
+    }
+}
+
+fn header() -> String {
+    "//\n// This file is generated by `minidsp-devtools codegen`. DO NOT EDIT.\n//\n".to_string()
+}
+
 fn gen<T: Target>() -> String {
     let device = T::device();
     dbg!(&device);
@@ -170,8 +507,7 @@ fn gen<T: Target>() -> String {
     let mut symbols = T::symbols();
     let s = generate_static_config(&mut symbols, &device).to_string();
 
-    "//\n// This file is generated by `minidsp-devtools codegen`. DO NOT EDIT.\n//\n".to_string()
-        + &s
+    header() + &s
 }
 
 fn gen_write<T: Target>(output: &Path) -> Result<()> {
@@ -179,16 +515,103 @@ fn gen_write<T: Target>(output: &Path) -> Result<()> {
     Ok(())
 }
 
-fn codegen_main(output: PathBuf) -> Result<()> {
-    gen_write::<m2x4hd::Target>(&output)?;
-    gen_write::<msharc4x8::Target>(&output)?;
-    gen_write::<m4x10hd::Target>(&output)?;
-    gen_write::<shd::Target>(&output)?;
-    gen_write::<ddrc24::Target>(&output)?;
-    gen_write::<nanodigi2x8::Target>(&output)?;
-    gen_write::<ddrc88bm::Target>(&output)?;
-    gen_write::<c8x12v2::Target>(&output)?;
-    // gen_write::<m2x4::Target>(&output)?;
-    gen_write::<m10x10hd::Target>(&output)?;
+/// Loads a device spec from `path`, supporting either `.toml` or `.json`.
+/// Returns an error if `path` isn't one of those two extensions.
+fn load_spec(path: &Path) -> Result<DeviceSpec> {
+    let contents = std::fs::read_to_string(path)?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => Ok(serde_json::from_str(&contents)?),
+        Some("toml") => Ok(toml::from_str(&contents)?),
+        _ => Err(anyhow::anyhow!(
+            "{}: not a recognized spec extension (expected .toml or .json)",
+            path.display()
+        )),
+    }
+}
+
+/// Generates a single device from a spec file and writes it to `output`,
+/// named after the spec file's stem (e.g. `m2x4hd.toml` -> `m2x4hd.rs`).
+fn gen_write_spec(output: &Path, spec_path: &Path) -> Result<()> {
+    let spec = load_spec(spec_path)?;
+    dbg!(&spec.device);
+
+    let mut symbols = spec.symbol_map();
+    let s = generate_static_config(&mut symbols, &spec.device).to_string();
+
+    let filename = spec_path.file_stem().unwrap().to_string_lossy().into_owned() + ".rs";
+    std::fs::write(output.join(filename), header() + &s)?;
+    Ok(())
+}
+
+/// Generates every device spec found in `input`, optionally restricted to the
+/// one whose file stem matches `device`. Entries that aren't `.toml` or
+/// `.json` files are skipped silently; spec files that fail to load or
+/// generate are skipped with a warning rather than aborting the whole
+/// directory.
+fn codegen_from_specs(output: &Path, input: &Path, device: Option<&str>) -> Result<()> {
+    for entry in std::fs::read_dir(input)? {
+        let path = entry?.path();
+        let is_spec = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("toml") | Some("json")
+        );
+        if !path.is_file() || !is_spec {
+            continue;
+        }
+        if let Some(device) = device {
+            if path.file_stem().and_then(|s| s.to_str()) != Some(device) {
+                continue;
+            }
+        }
+        if let Err(err) = gen_write_spec(output, &path) {
+            eprintln!("skipping {}: {:#}", path.display(), err);
+        }
+    }
+    Ok(())
+}
+
+fn codegen_builtin(output: &Path) -> Result<()> {
+    gen_write::<m2x4hd::Target>(output)?;
+    gen_write::<msharc4x8::Target>(output)?;
+    gen_write::<m4x10hd::Target>(output)?;
+    gen_write::<shd::Target>(output)?;
+    gen_write::<ddrc24::Target>(output)?;
+    gen_write::<nanodigi2x8::Target>(output)?;
+    gen_write::<ddrc88bm::Target>(output)?;
+    gen_write::<c8x12v2::Target>(output)?;
+    // gen_write::<m2x4::Target>(output)?;
+    gen_write::<m10x10hd::Target>(output)?;
     Ok(())
 }
+
+fn codegen_main(output: PathBuf, input: Option<PathBuf>, device: Option<String>) -> Result<()> {
+    match input {
+        Some(input) => codegen_from_specs(&output, &input, device.as_deref()),
+        None if device.is_some() => Err(anyhow::anyhow!("--device requires --input")),
+        None => codegen_builtin(&output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_format_detect() {
+        let cases = [
+            ("foo.bin", RecordingFormat::Binary),
+            ("foo.bin.zst", RecordingFormat::Binary),
+            ("foo.txt", RecordingFormat::Text),
+            ("foo.zst", RecordingFormat::Text),
+            ("foo", RecordingFormat::Text),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(
+                RecordingFormat::detect(Path::new(path)),
+                expected,
+                "path: {}",
+                path
+            );
+        }
+    }
+}